@@ -6,9 +6,12 @@ use std::{
     fs::{
         rename,
         read_dir,
+        File,
     },
     io,
+    sync::Mutex,
 };
+use rayon::prelude::*;
 use clap::{
     self,
     Parser,
@@ -35,6 +38,10 @@ enum Error {
     FfmpegError(ffmpeg::Error),
     TinyTemplateError(tinytemplate::error::Error),
     IoError(std::io::Error),
+    SerdeJsonError(serde_json::Error),
+    #[cfg(feature = "yaml")]
+    SerdeYamlError(serde_yaml::Error),
+    ImageError(image::ImageError),
 }
 
 impl From<chrono::format::ParseError> for Error {
@@ -61,6 +68,39 @@ impl From<io::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::SerdeJsonError(err)
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl From<serde_yaml::Error> for Error {
+    fn from(err: serde_yaml::Error) -> Error {
+        Error::SerdeYamlError(err)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Error {
+        Error::ImageError(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ReportFormat {
+    Json,
+    Yaml,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum OnConflict {
+    Error,
+    Skip,
+    Number,
+    Hash,
+}
+
 #[derive(clap::Parser, Debug)]
 #[clap(group(clap::ArgGroup::new("path").required(true).args(&["dir", "file"])))]
 #[command(author, version, about, long_about = None)]
@@ -79,6 +119,24 @@ struct Args {
 
     #[arg(long, action = clap::ArgAction::SetTrue)]
     run: bool,
+
+    #[arg(long, value_hint = clap::ValueHint::DirPath)]
+    report: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value = "json")]
+    report_format: ReportFormat,
+
+    #[arg(long, value_enum, default_value = "error")]
+    on_conflict: OnConflict,
+
+    #[arg(long)]
+    thumbnail: Option<String>,
+
+    #[arg(long)]
+    thumbnail_time: Option<f64>,
+
+    #[arg(long, default_value_t = 1)]
+    jobs: usize,
 }
 
 fn main() -> Result<(), Error> {
@@ -95,35 +153,68 @@ fn main() -> Result<(), Error> {
     let mut tt = TinyTemplate::new();
     tt.add_template("main", &args.template)?;
 
+    let mut thumbnail_tt = TinyTemplate::new();
+    if let Some(thumbnail_template) = &args.thumbnail {
+        thumbnail_tt.add_template("thumbnail", thumbnail_template)?;
+    }
+    let thumbnail_tt = args.thumbnail.as_ref().map(|_| &thumbnail_tt);
+
     ffmpeg::init()?;
     if for_dir {
-        process_dir(&path, &args, &tt)
+        process_dir(&path, &args, &tt, thumbnail_tt)
     } else {
-        process_file(&path, &args, &tt)
+        let rename_lock = Mutex::new(());
+        process_file(&path, &args, &tt, thumbnail_tt, &rename_lock)
     }
 }
 
-fn process_dir(path: &Path, args: &Args, tt: &TinyTemplate) -> Result<(), Error> {
-    if !path.is_dir() {
-        return Err(Error::Message(format!("Couldn't get parent dir from: {:?}", path)));
-    }
+fn collect_files(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
     let paths = read_dir(path)?.filter_map(|e| e.ok()).map(|e| e.path()).collect::<Vec<_>>();
     for child_path in paths {
         if child_path.is_dir() {
-            process_dir(&child_path, args, tt)?;
+            files.extend(collect_files(&child_path)?);
         } else {
-            match process_file(&child_path, args, tt) {
-                Err(err) => {
-                    eprintln!("Error occurred in the path: {:?}\n{:?}", child_path, err);
-                },
-                _ => (),
-            }
+            files.push(child_path);
+        }
+    }
+    Ok(files)
+}
+
+fn process_dir(path: &Path, args: &Args, tt: &TinyTemplate, thumbnail_tt: Option<&TinyTemplate>) -> Result<(), Error> {
+    if !path.is_dir() {
+        return Err(Error::Message(format!("Couldn't get parent dir from: {:?}", path)));
+    }
+
+    let files = collect_files(path)?;
+    let rename_lock = Mutex::new(());
+
+    let results: Vec<(PathBuf, Result<(), Error>)> = if args.jobs > 1 {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(args.jobs)
+            .build()
+            .map_err(|err| Error::Message(format!("{}", err)))?;
+        pool.install(|| {
+            files.par_iter()
+                .map(|file_path| (file_path.clone(), process_file(file_path, args, tt, thumbnail_tt, &rename_lock)))
+                .collect()
+        })
+    } else {
+        files.iter()
+            .map(|file_path| (file_path.clone(), process_file(file_path, args, tt, thumbnail_tt, &rename_lock)))
+            .collect()
+    };
+
+    for (file_path, result) in results {
+        if let Err(err) = result {
+            eprintln!("Error occurred in the path: {:?}\n{:?}", file_path, err);
         }
     }
+
     Ok(())
 }
 
-fn process_file(path: &Path, args: &Args, tt: &TinyTemplate) -> Result<(), Error> {
+fn process_file(path: &Path, args: &Args, tt: &TinyTemplate, thumbnail_tt: Option<&TinyTemplate>, rename_lock: &Mutex<()>) -> Result<(), Error> {
     if let Some(filename) = path.file_name() {
         let ctx = ffmpeg::format::input(&path);
         let Ok(ctx) = ctx else {
@@ -142,23 +233,65 @@ fn process_file(path: &Path, args: &Args, tt: &TinyTemplate) -> Result<(), Error
         metadata_map.insert("original".into(), filename.clone().into());
         metadata_map.insert("original_filename".into(), filename.clone().into());
 
+        // Hashing reads the whole file, so only pay for it when something
+        // can actually observe the result: a template referencing {hash} /
+        // {hash_short} / {size}, or --on-conflict hash needing it to name
+        // the de-duplicated target.
+        let hash_short = if needs_hash_and_size(args) {
+            let (hash, size) = hash_and_size_of_file(path)?;
+            let hash_short = hash[..16].to_string();
+            metadata_map.insert("hash".into(), hash.into());
+            metadata_map.insert("hash_short".into(), hash_short.clone().into());
+            metadata_map.insert("size".into(), size.into());
+            hash_short
+        } else {
+            String::new()
+        };
+
         let Some(parent_dir) = path.parent() else {
             return Err(Error::Message(format!("Couldn't get parent dir from: {:?}", path)));
         };
 
+        if let Some(report_dir) = &args.report {
+            write_report(report_dir, path, &metadata_value, args.report_format)?;
+        }
+
         let new_filename = tt.render("main", &metadata_value)?;
-        let new_path = parent_dir.join(&new_filename);
+        let candidate_path = parent_dir.join(&new_filename);
 
-        if new_path.exists() {
-            return Err(Error::Message(format!("File already exists at new path: {:?}", new_path)));
-        }
+        // Conflict-detection and rename must happen as one atomic step so that
+        // concurrent workers processing different source files never race on
+        // the same target path.
+        let new_path = {
+            let _guard = rename_lock.lock().expect("rename_lock poisoned");
+
+            let Some(new_path) = resolve_conflict(&candidate_path, &hash_short, args.on_conflict)? else {
+                eprintln!("SKIP: {:?} already exists at new path: {:?}", filename, candidate_path);
+                return Ok(());
+            };
+
+            if args.run {
+                rename(&path, &new_path)?;
+            }
+
+            new_path
+        };
+
+        let new_filename = new_path.file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or(new_filename);
 
         if args.run {
-            rename(&path, &new_path)?;
             eprintln!("rename {:?} {:?}", filename, new_filename);
         } else {
             eprintln!("DRY_RUN: rename {:?} {:?}", filename, new_filename);
         }
+
+        if let Some(thumbnail_tt) = thumbnail_tt {
+            if args.run {
+                generate_thumbnail(&new_path, parent_dir, &metadata_value, thumbnail_tt, args.thumbnail_time)?;
+            } else {
+                eprintln!("DRY_RUN: skip thumbnail generation for {:?}", filename);
+            }
+        }
     }
 
     Ok(())
@@ -202,6 +335,17 @@ fn get_metadata_value(ctx: ffmpeg::format::context::input::Input, args: &Args) -
         stream_map.insert("discard".into(), format!("{:?}", stream.discard()).into());
         insert_rational_value(&mut stream_map, "rate", stream.rate());
 
+        let mut stream_metadata_map = Map::new();
+        for (k, v) in stream.metadata().iter() {
+            let value = if k == "creation_time" {
+                format_datetime(v.into(), &args.datetime_format)?
+            } else {
+                v.into()
+            };
+            stream_metadata_map.insert(k.into(), value);
+        }
+        stream_map.insert("metadata".into(), stream_metadata_map.into());
+
         let codec = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
 
         stream_map.insert("codec_medium".into(), format!("{:?}", codec.medium()).into());
@@ -263,9 +407,326 @@ fn get_metadata_value(ctx: ffmpeg::format::context::input::Input, args: &Args) -
     }
 
     root_map.insert("streams".into(), streams.into());
+
+    let mut programs: Vec<Value> = Vec::new();
+    for program in ctx.programs() {
+        let mut program_map = Map::new();
+        program_map.insert("id".into(), program.id().into());
+
+        let stream_indices: Vec<Value> = program.streams().map(|stream| stream.index().into()).collect();
+        program_map.insert("streams".into(), stream_indices.into());
+
+        let mut program_metadata_map = Map::new();
+        for (k, v) in program.metadata().iter() {
+            program_metadata_map.insert(k.into(), v.into());
+        }
+        program_map.insert("metadata".into(), program_metadata_map.into());
+
+        programs.push(program_map.into());
+    }
+    root_map.insert("programs".into(), programs.into());
+
+    let mut chapters: Vec<Value> = Vec::new();
+    for chapter in ctx.chapters() {
+        let mut chapter_map = Map::new();
+        chapter_map.insert("id".into(), chapter.id().into());
+        insert_rational_value(&mut chapter_map, "time_base", chapter.time_base());
+        chapter_map.insert("start".into(), chapter.start().into());
+        chapter_map.insert("start_in_sec".into(), (chapter.start() as f64 * f64::from(chapter.time_base())).into());
+        chapter_map.insert("end".into(), chapter.end().into());
+        chapter_map.insert("end_in_sec".into(), (chapter.end() as f64 * f64::from(chapter.time_base())).into());
+
+        let mut chapter_metadata_map = Map::new();
+        for (k, v) in chapter.metadata().iter() {
+            chapter_metadata_map.insert(k.into(), v.into());
+        }
+        chapter_map.insert("metadata".into(), chapter_metadata_map.into());
+
+        chapters.push(chapter_map.into());
+    }
+
+    root_map.insert("chapters".into(), chapters.into());
     Ok(Value::Object(root_map))
 }
 
+fn resolve_conflict(candidate_path: &Path, hash_short: &str, on_conflict: OnConflict) -> Result<Option<PathBuf>, Error> {
+    if !candidate_path.exists() {
+        return Ok(Some(candidate_path.to_path_buf()));
+    }
+
+    match on_conflict {
+        OnConflict::Error => Err(Error::Message(format!("File already exists at new path: {:?}", candidate_path))),
+        OnConflict::Skip => Ok(None),
+        OnConflict::Number => {
+            let Some(parent_dir) = candidate_path.parent() else {
+                return Err(Error::Message(format!("Couldn't get parent dir from: {:?}", candidate_path)));
+            };
+            let stem = candidate_path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            let extension = candidate_path.extension().map(|e| e.to_string_lossy().into_owned());
+
+            let mut n = 1u64;
+            loop {
+                let numbered_filename = match &extension {
+                    Some(extension) => format!("{} ({}).{}", stem, n, extension),
+                    None => format!("{} ({})", stem, n),
+                };
+                let numbered_path = parent_dir.join(numbered_filename);
+                if !numbered_path.exists() {
+                    return Ok(Some(numbered_path));
+                }
+                n += 1;
+            }
+        },
+        OnConflict::Hash => {
+            let Some(parent_dir) = candidate_path.parent() else {
+                return Err(Error::Message(format!("Couldn't get parent dir from: {:?}", candidate_path)));
+            };
+            let stem = candidate_path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            let extension = candidate_path.extension().map(|e| e.to_string_lossy().into_owned());
+
+            let hashed_filename = match &extension {
+                Some(extension) => format!("{}-{}.{}", stem, hash_short, extension),
+                None => format!("{}-{}", stem, hash_short),
+            };
+            let hashed_path = parent_dir.join(hashed_filename);
+            if !hashed_path.exists() {
+                return Ok(Some(hashed_path));
+            }
+
+            // Byte-identical duplicates hash the same, so the hash suffix
+            // alone can still collide (the previous duplicate moved into
+            // `stem-<hash>.ext` already). Fall back to an additional numeric
+            // suffix rather than renaming over it, same as the `Number` mode.
+            let mut n = 1u64;
+            loop {
+                let numbered_filename = match &extension {
+                    Some(extension) => format!("{} ({})-{}.{}", stem, n, hash_short, extension),
+                    None => format!("{} ({})-{}", stem, n, hash_short),
+                };
+                let numbered_path = parent_dir.join(numbered_filename);
+                if !numbered_path.exists() {
+                    return Ok(Some(numbered_path));
+                }
+                n += 1;
+            }
+        },
+    }
+}
+
+fn needs_hash_and_size(args: &Args) -> bool {
+    if matches!(args.on_conflict, OnConflict::Hash) {
+        return true;
+    }
+
+    let references_hash_or_size = |template: &str| {
+        ["hash", "hash_short", "size"].iter().any(|var| template.contains(var))
+    };
+
+    references_hash_or_size(&args.template)
+        || args.thumbnail.as_deref().map(references_hash_or_size).unwrap_or(false)
+}
+
+fn hash_and_size_of_file(path: &Path) -> Result<(String, u64), Error> {
+    use std::io::Read;
+
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = [0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        size += read as u64;
+    }
+    Ok((hasher.finalize().to_hex().to_string(), size))
+}
+
+const THUMBNAIL_MAX_DIMENSION: u32 = 640;
+const THUMBNAIL_DEFAULT_POSITION_RATIO: f64 = 0.1;
+
+fn generate_thumbnail(path: &Path, parent_dir: &Path, metadata_value: &Value, thumbnail_tt: &TinyTemplate, thumbnail_time: Option<f64>) -> Result<(), Error> {
+    let mut ctx = ffmpeg::format::input(&path)?;
+
+    let Some(stream) = ctx.streams().best(ffmpeg::media::Type::Video) else {
+        return Err(Error::Message(format!("No video stream found in: {:?}", path)));
+    };
+    let stream_index = stream.index();
+    let time_base = stream.time_base();
+    let duration_in_sec = stream.duration() as f64 * f64::from(time_base);
+
+    // Rotation is carried as a "rotate" metadata tag on older files, or as a
+    // display matrix side data entry on newer phone/camera captures, rather
+    // than as pixel data, so it has to be applied to the decoded frame
+    // explicitly. The side data entry (when present) reflects the actual
+    // orientation more reliably, so it takes precedence over the tag. The
+    // display matrix angle is counterclockwise while the tag (and the
+    // imageops rotations below) are clockwise, so it is negated here.
+    let rotation_degrees = display_matrix_rotation_degrees(&stream)
+        .map(|degrees| (-degrees).rem_euclid(360))
+        .or_else(|| {
+            stream.metadata().get("rotate")
+                .and_then(|rotate| rotate.parse::<i64>().ok())
+                .map(|degrees| degrees.rem_euclid(360))
+        })
+        .unwrap_or(0);
+
+    let seek_target_in_sec = thumbnail_time.unwrap_or_else(|| {
+        if duration_in_sec > 0.0 {
+            duration_in_sec * THUMBNAIL_DEFAULT_POSITION_RATIO
+        } else {
+            0.0
+        }
+    });
+
+    let mut decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?.decoder().video()?;
+
+    if seek_target_in_sec > 0.0 {
+        let seek_target_stream_ts = (seek_target_in_sec / f64::from(time_base)) as i64;
+        ctx.seek(seek_target_stream_ts, ..seek_target_stream_ts)?;
+    }
+
+    let mut scaler: Option<ffmpeg::software::scaling::Context> = None;
+    let mut decoded = ffmpeg::util::frame::Video::empty();
+    let mut scaled = ffmpeg::util::frame::Video::empty();
+    let mut decoded_any_frame = false;
+
+    'packets: for (packet_stream, packet) in ctx.packets() {
+        if packet_stream.index() != stream_index {
+            continue;
+        }
+        decoder.send_packet(&packet)?;
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            let (width, height) = bounded_thumbnail_size(decoded.width(), decoded.height(), THUMBNAIL_MAX_DIMENSION);
+            let scaler = scaler.get_or_insert(ffmpeg::software::scaling::Context::get(
+                decoder.format(),
+                decoder.width(),
+                decoder.height(),
+                ffmpeg::format::Pixel::RGB24,
+                width,
+                height,
+                ffmpeg::software::scaling::Flags::BILINEAR,
+            )?);
+            scaler.run(&decoded, &mut scaled)?;
+            decoded_any_frame = true;
+            break 'packets;
+        }
+    }
+
+    if !decoded_any_frame {
+        return Err(Error::Message(format!("Couldn't decode a frame for thumbnail from: {:?}", path)));
+    }
+
+    let thumbnail_filename = thumbnail_tt.render("thumbnail", metadata_value)?;
+    let thumbnail_path = parent_dir.join(thumbnail_filename);
+
+    let image_buffer = image::RgbImage::from_raw(scaled.width(), scaled.height(), scaled.data(0).to_vec())
+        .ok_or_else(|| Error::Message(format!("Couldn't build a thumbnail image buffer for: {:?}", path)))?;
+
+    match rotation_degrees {
+        90 => image::imageops::rotate90(&image_buffer).save(&thumbnail_path)?,
+        180 => image::imageops::rotate180(&image_buffer).save(&thumbnail_path)?,
+        270 => image::imageops::rotate270(&image_buffer).save(&thumbnail_path)?,
+        _ => image_buffer.save(&thumbnail_path)?,
+    }
+
+    Ok(())
+}
+
+// Mirrors libavutil's av_display_rotation_get(): the display matrix side
+// data is a row-major 3x3 matrix in 16.16 fixed point (the last row is
+// 2.30 fixed point and isn't needed for the rotation angle), and the
+// rotation it encodes is the negated angle between the transformed and
+// untransformed x axes.
+fn display_matrix_rotation_degrees(stream: &ffmpeg::format::stream::Stream) -> Option<i64> {
+    let side_data = stream.side_data().find(|side_data| side_data.kind() == ffmpeg::codec::packet::side_data::Type::DisplayMatrix)?;
+    let data = side_data.data();
+    if data.len() < 36 {
+        return None;
+    }
+
+    let read_fixed_point = |index: usize| -> f64 {
+        let bytes: [u8; 4] = data[index * 4..index * 4 + 4].try_into().expect("slice has exactly 4 bytes");
+        i32::from_ne_bytes(bytes) as f64 / 65536.0
+    };
+    let (m0, m1, m3, m4) = (read_fixed_point(0), read_fixed_point(1), read_fixed_point(3), read_fixed_point(4));
+
+    let scale0 = m0.hypot(m3);
+    let scale1 = m1.hypot(m4);
+    if scale0 == 0.0 || scale1 == 0.0 {
+        return None;
+    }
+
+    let rotation = -(m1 / scale1).atan2(m0 / scale0).to_degrees();
+    let snapped_to_quarter_turn = (rotation / 90.0).round() as i64 * 90;
+    Some(snapped_to_quarter_turn.rem_euclid(360))
+}
+
+fn bounded_thumbnail_size(width: u32, height: u32, max_dimension: u32) -> (u32, u32) {
+    if width <= max_dimension && height <= max_dimension {
+        return (width, height);
+    }
+    if width >= height {
+        let scaled_height = (height as f64 * max_dimension as f64 / width as f64).round() as u32;
+        (max_dimension, scaled_height.max(1))
+    } else {
+        let scaled_width = (width as f64 * max_dimension as f64 / height as f64).round() as u32;
+        (scaled_width.max(1), max_dimension)
+    }
+}
+
+// Mirrors the source file's own directory structure under `report_dir`
+// (rather than keying by bare filename) so that same-named files in
+// different source directories, e.g. `Season 1/episode01.mp4` and
+// `Season 2/episode01.mp4`, get distinct sidecars instead of clobbering
+// each other. `..`/root components are dropped rather than followed, so the
+// report always lands inside `report_dir`.
+fn report_relative_path(source_path: &Path) -> PathBuf {
+    source_path.components()
+        .filter_map(|component| match component {
+            std::path::Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
+
+fn write_report(report_dir: &Path, source_path: &Path, metadata_value: &Value, format: ReportFormat) -> Result<(), Error> {
+    let relative_path = report_relative_path(source_path);
+
+    let extension = match format {
+        ReportFormat::Json => "json",
+        ReportFormat::Yaml => "yaml",
+    };
+    let report_path = report_dir.join(format!("{}.{}", relative_path.display(), extension));
+
+    let Some(report_parent_dir) = report_path.parent() else {
+        return Err(Error::Message(format!("Couldn't get parent dir from: {:?}", report_path)));
+    };
+    std::fs::create_dir_all(report_parent_dir)?;
+
+    match format {
+        ReportFormat::Json => {
+            let file = File::create(report_path)?;
+            serde_json::to_writer_pretty(file, metadata_value)?;
+        },
+        ReportFormat::Yaml => {
+            #[cfg(feature = "yaml")]
+            {
+                let file = File::create(report_path)?;
+                serde_yaml::to_writer(file, metadata_value)?;
+            }
+            #[cfg(not(feature = "yaml"))]
+            {
+                return Err(Error::Message("YAML report support requires building with the \"yaml\" feature".into()));
+            }
+        },
+    }
+
+    Ok(())
+}
+
 fn format_datetime<'a>(value: Value, fmt: &'a str) -> Result<Value, Error> {
     let Some(parse_target_str) = value.as_str() else {
         return Err(Error::Message("Value couldn't be converted to a string.".into()));